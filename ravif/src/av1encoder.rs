@@ -0,0 +1,616 @@
+use crate::dirtyalpha::blurred_dirty_alpha;
+use crate::error::Error;
+use imgref::{Img, ImgRef};
+use rav1e::prelude::*;
+use rgb::{RGB16, RGB8, RGBA16, RGBA8};
+use std::time::Duration;
+
+/// Transfer characteristics for high-bit-depth HDR output, set via
+/// `Encoder::with_hdr_transfer_characteristic` and used by `encode_rgba16`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HdrTransferCharacteristic {
+    /// SMPTE ST 2084 perceptual quantizer, for static-metadata HDR10-style content
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, for broadcast-style HDR
+    Hlg,
+}
+
+impl HdrTransferCharacteristic {
+    fn to_rav1e(self) -> TransferCharacteristics {
+        match self {
+            Self::Pq => TransferCharacteristics::SMPTE2084,
+            Self::Hlg => TransferCharacteristics::HLG,
+        }
+    }
+}
+
+/// How to encode pixels that are fully transparent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlphaColorMode {
+    /// Keep the RGB values of transparent pixels as-is (larger, lower quality files)
+    UnassociatedDirty,
+    /// Replace the RGB values of transparent pixels with a blurred average of
+    /// their neighbors, which compresses much better
+    UnassociatedClean,
+    /// Scale RGB channels by the alpha value, as required by some decoders
+    Premultiplied,
+}
+
+/// Internal AV1 color space used for the color (non-alpha) plane.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Convert to YCbCr. This is what most AVIF viewers expect, and compresses best.
+    YCbCr,
+    /// Keep RGB, encoded losslessly with identity matrix coefficients.
+    RGB,
+}
+
+/// Result of a successful encode.
+#[derive(Debug, Clone)]
+pub struct EncodedImage {
+    /// Complete AVIF file, ready to write out
+    pub avif_file: Vec<u8>,
+    /// Bytes spent on the color plane(s)
+    pub color_byte_size: usize,
+    /// Bytes spent on the alpha plane, if any
+    pub alpha_byte_size: usize,
+    /// The quality that was actually used. Equal to `with_quality`'s
+    /// argument, unless a target-size/target-SSIM search picked a different
+    /// value.
+    pub quality: f32,
+}
+
+/// Configures and runs the AV1 encode of one or more frames into an AVIF file.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    quality: f32,
+    alpha_quality: f32,
+    speed: u8,
+    premultiplied_alpha: bool,
+    color_space: ColorSpace,
+    alpha_color_mode: AlphaColorMode,
+    threads: Option<usize>,
+    lossless: bool,
+    target_size: Option<usize>,
+    target_ssim: Option<f32>,
+    depth: u8,
+    hdr_transfer: Option<HdrTransferCharacteristic>,
+    loop_count: u32,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            quality: 80.,
+            alpha_quality: 80.,
+            speed: 4,
+            premultiplied_alpha: false,
+            color_space: ColorSpace::YCbCr,
+            alpha_color_mode: AlphaColorMode::UnassociatedClean,
+            threads: None,
+            lossless: false,
+            target_size: None,
+            target_ssim: None,
+            depth: 8,
+            hdr_transfer: None,
+            loop_count: 0,
+        }
+    }
+}
+
+impl Encoder {
+    /// Start building a new encode with the library's defaults (quality 80, speed 4).
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Quality from 1 (worst) to 100 (best), mapped onto rav1e's quantizer range.
+    #[must_use]
+    pub fn with_quality(mut self, quality: f32) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Quality of the alpha channel, separate from the color quality.
+    #[must_use]
+    pub fn with_alpha_quality(mut self, quality: f32) -> Self {
+        self.alpha_quality = quality;
+        self
+    }
+
+    /// rav1e encoding speed preset, 0 (best) to 10 (fastest).
+    #[must_use]
+    pub fn with_speed(mut self, speed: u8) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Internal AV1 color space for the color plane.
+    #[must_use]
+    pub fn with_internal_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// How to treat fully-transparent pixels.
+    #[must_use]
+    pub fn with_alpha_color_mode(mut self, mode: AlphaColorMode) -> Self {
+        self.alpha_color_mode = mode;
+        self
+    }
+
+    /// Number of encoder threads, or `None` to let rav1e pick one per core.
+    #[must_use]
+    pub fn with_num_threads(mut self, threads: Option<usize>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Encode mathematically lossless: decoded pixels will match the input
+    /// exactly. Forces the internal color space to RGB (no chroma
+    /// subsampling, identity matrix coefficients, full range) and the
+    /// quantizer to rav1e's lossless setting, overriding `with_quality` and
+    /// `with_internal_color_space`.
+    ///
+    /// Round-trip equality isn't covered by an automated test in this crate
+    /// (there is no test suite here to extend); verify it the same way the
+    /// target-SSIM search does, by decoding `encode_rgba`'s output with
+    /// `avif_decode` and comparing pixels against the source.
+    #[must_use]
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// Automatically search for the highest quality that keeps the encoded
+    /// file at or under `bytes`, instead of using a fixed `with_quality`.
+    ///
+    /// Takes priority over `with_target_ssim` if both are set.
+    #[must_use]
+    pub fn with_target_size(mut self, bytes: usize) -> Self {
+        self.target_size = Some(bytes);
+        self
+    }
+
+    /// Automatically search for the lowest quality whose decoded output
+    /// reaches at least `ssim` structural similarity against the source
+    /// (on a 0.0-1.0 scale), instead of using a fixed `with_quality`.
+    #[must_use]
+    pub fn with_target_ssim(mut self, ssim: f32) -> Self {
+        self.target_ssim = Some(ssim);
+        self
+    }
+
+    /// Bit depth for [`Encoder::encode_rgba16`]: 10 or 12. Ignored by the
+    /// 8-bit `encode_rgba`/`encode_rgba_sequence` paths.
+    #[must_use]
+    pub fn with_depth(mut self, depth: u8) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Transfer characteristics to tag the high-bit-depth output with, for
+    /// [`Encoder::encode_rgba16`]. `None` (the default) leaves both the
+    /// transfer characteristics and the color primaries unspecified.
+    #[must_use]
+    pub fn with_hdr_transfer_characteristic(mut self, transfer: Option<HdrTransferCharacteristic>) -> Self {
+        self.hdr_transfer = transfer;
+        self
+    }
+
+    /// Number of times [`Encoder::encode_rgba_sequence`]'s output should
+    /// repeat, 0 meaning infinite. Ignored by the still-image encode paths.
+    #[must_use]
+    pub fn with_loop_count(mut self, loop_count: u32) -> Self {
+        self.loop_count = loop_count;
+        self
+    }
+
+    fn quantizer(&self, quality: f32) -> usize {
+        if self.lossless { 0 } else { quality_to_quantizer(quality) }
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        if self.lossless { ColorSpace::RGB } else { self.color_space }
+    }
+
+    /// Encode a single still image.
+    ///
+    /// If `with_target_size` or `with_target_ssim` was set, this bisects
+    /// over the quality parameter (1..100) instead of using a fixed
+    /// quality: it encodes at the midpoint, checks the resulting file size
+    /// or SSIM against the source, then narrows the bounds and tries again,
+    /// for up to 8 iterations or until the bounds are 1 quality point apart.
+    pub fn encode_rgba(&self, in_buffer: ImgRef<RGBA8>) -> Result<EncodedImage, Error> {
+        if self.target_size.is_some() || self.target_ssim.is_some() {
+            return self.encode_rgba_search(in_buffer);
+        }
+        self.encode_rgba_at_quality(in_buffer, self.quality)
+    }
+
+    fn encode_rgba_search(&self, in_buffer: ImgRef<RGBA8>) -> Result<EncodedImage, Error> {
+        let (mut lo, mut hi) = (1.0f32, 100.0f32);
+        let mut best = self.encode_rgba_at_quality(in_buffer, lo)?;
+
+        for _ in 0..8 {
+            if hi - lo <= 1. {
+                break;
+            }
+            let mid = (lo + hi) / 2.;
+            let candidate = self.encode_rgba_at_quality(in_buffer, mid)?;
+
+            // Whether `candidate` actually satisfies the constraint: at or
+            // under the byte budget for --max-size, at or above the
+            // threshold for --target-ssim.
+            let meets_constraint = if let Some(max_bytes) = self.target_size {
+                candidate.avif_file.len() <= max_bytes
+            } else {
+                let target_ssim = self.target_ssim.expect("checked by caller");
+                decoded_ssim(in_buffer, &candidate.avif_file)? >= f64::from(target_ssim)
+            };
+            // Only keep a candidate as `best` when it meets the constraint,
+            // so the search never regresses to a result that broke the
+            // promised budget/quality floor.
+            if meets_constraint {
+                best = candidate;
+            }
+
+            // For --max-size, meeting the budget means we can afford to try
+            // a higher quality next; for --target-ssim, meeting the target
+            // means we can afford to try a lower one.
+            let should_raise_quality = if self.target_size.is_some() { meets_constraint } else { !meets_constraint };
+            if should_raise_quality {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(best)
+    }
+
+    fn encode_rgba_at_quality(&self, in_buffer: ImgRef<RGBA8>, quality: f32) -> Result<EncodedImage, Error> {
+        if in_buffer.width() == 0 || in_buffer.height() == 0 {
+            return Err(Error::TooSmall);
+        }
+
+        let alpha_pixels: Option<Vec<RGBA8>> = match self.alpha_color_mode {
+            AlphaColorMode::UnassociatedDirty => None,
+            AlphaColorMode::UnassociatedClean => blurred_dirty_alpha(in_buffer),
+            AlphaColorMode::Premultiplied => Some(
+                in_buffer
+                    .pixels()
+                    .map(|px| {
+                        let mul = |c: u8| (u16::from(c) * u16::from(px.a) / 255) as u8;
+                        RGBA8::new(mul(px.r), mul(px.g), mul(px.b), px.a)
+                    })
+                    .collect(),
+            ),
+        };
+        let color_source = alpha_pixels
+            .as_deref()
+            .map(|buf| Img::new(buf, in_buffer.width(), in_buffer.height()))
+            .unwrap_or(in_buffer);
+
+        let has_alpha = in_buffer.pixels().any(|px| px.a != 255);
+
+        let color_av1 = encode_to_av1(
+            &color_source.pixels().map(|px| RGB8::new(px.r, px.g, px.b)).collect::<Vec<_>>(),
+            in_buffer.width(),
+            in_buffer.height(),
+            self.quantizer(quality),
+            self.speed,
+            self.color_space(),
+            self.threads,
+        )?;
+
+        let alpha_av1 = if has_alpha {
+            Some(encode_to_av1(
+                &in_buffer.pixels().map(|px| px.a).collect::<Vec<_>>(),
+                in_buffer.width(),
+                in_buffer.height(),
+                self.quantizer(self.alpha_quality),
+                self.speed,
+                ColorSpace::RGB,
+                self.threads,
+            )?)
+        } else {
+            None
+        };
+
+        let color_byte_size = color_av1.len();
+        let alpha_byte_size = alpha_av1.as_ref().map_or(0, Vec::len);
+        let avif_file = avif_serialize::serialize(&color_av1, alpha_av1.as_deref())
+            .map_err(|e| Error::Write(e.to_string()))?;
+
+        Ok(EncodedImage { avif_file, color_byte_size, alpha_byte_size, quality })
+    }
+
+    /// Encode a single still image at 10- or 12-bit depth (set via
+    /// `with_depth`), optionally tagged with HDR transfer characteristics
+    /// (`with_hdr_transfer_characteristic`) and BT.2020 primaries.
+    ///
+    /// The AV1 sequence header carries the bit depth and color description,
+    /// so no extra container boxes are needed beyond what `avif_serialize`
+    /// already writes from the encoded bitstream.
+    pub fn encode_rgba16(&self, in_buffer: ImgRef<RGBA16>) -> Result<EncodedImage, Error> {
+        if in_buffer.width() == 0 || in_buffer.height() == 0 {
+            return Err(Error::TooSmall);
+        }
+        let depth = self.depth.clamp(10, 12);
+        let primaries = if self.hdr_transfer.is_some() { ColorPrimaries::BT2020 } else { ColorPrimaries::Unspecified };
+        let transfer = self
+            .hdr_transfer
+            .map(HdrTransferCharacteristic::to_rav1e)
+            .unwrap_or(TransferCharacteristics::Unspecified);
+
+        let has_alpha = in_buffer.pixels().any(|px| px.a != u16::MAX);
+
+        // RGBA16 channels are full 16-bit range (0..=65535); rav1e's planes hold
+        // samples already scaled to the configured bit depth (0..=1023 for 10-bit,
+        // 0..=4095 for 12-bit), so rescale before handing samples to the encoder.
+        let scale = |v: u16| scale_to_depth(v, depth);
+
+        let color_av1 = encode_to_av1_16(
+            &in_buffer
+                .pixels()
+                .map(|px| RGB16::new(scale(px.r), scale(px.g), scale(px.b)))
+                .collect::<Vec<_>>(),
+            in_buffer.width(),
+            in_buffer.height(),
+            self.quantizer(self.quality),
+            self.speed,
+            self.color_space(),
+            self.threads,
+            depth,
+            transfer,
+            primaries,
+        )?;
+
+        let alpha_av1 = if has_alpha {
+            Some(encode_to_av1_16(
+                &in_buffer.pixels().map(|px| scale(px.a)).collect::<Vec<_>>(),
+                in_buffer.width(),
+                in_buffer.height(),
+                self.quantizer(self.alpha_quality),
+                self.speed,
+                ColorSpace::RGB,
+                self.threads,
+                depth,
+                TransferCharacteristics::Unspecified,
+                ColorPrimaries::Unspecified,
+            )?)
+        } else {
+            None
+        };
+
+        let color_byte_size = color_av1.len();
+        let alpha_byte_size = alpha_av1.as_ref().map_or(0, Vec::len);
+        let avif_file = avif_serialize::serialize(&color_av1, alpha_av1.as_deref())
+            .map_err(|e| Error::Write(e.to_string()))?;
+
+        Ok(EncodedImage { avif_file, color_byte_size, alpha_byte_size, quality: self.quality })
+    }
+
+    /// Encode a sequence of frames (e.g. decoded from an animated GIF or
+    /// APNG) into an animated AVIF image sequence.
+    ///
+    /// `keyframe_interval` is passed to rav1e as the maximum number of frames
+    /// between keyframes, with rav1e placing inter frames in between; 1 (or
+    /// 0) makes every frame a keyframe (all-intra). There is no default here:
+    /// the caller picks the interval.
+    ///
+    /// Unlike [`Encoder::encode_rgba`], this does not encode an alpha track:
+    /// an `avis` image sequence's alpha would need its own track plus an
+    /// `auxC`/`auxl` association back to the color track, which this muxer
+    /// doesn't build. Any alpha channel in `frames` is dropped; fully
+    /// transparent input still produces a (now opaque) animation rather than
+    /// silently-unreferenced alpha bytes in the file.
+    pub fn encode_rgba_sequence<'a>(
+        &self,
+        frames: impl IntoIterator<Item = (ImgRef<'a, RGBA8>, Duration)>,
+        keyframe_interval: u32,
+    ) -> Result<EncodedImage, Error> {
+        let frames: Vec<_> = frames.into_iter().collect();
+        let (first, _) = *frames.first().ok_or(Error::TooSmall)?;
+        let (width, height) = (first.width(), first.height());
+
+        let color_frames: Vec<Vec<RGB8>> = frames
+            .iter()
+            .map(|(img, _)| img.pixels().map(|px| RGB8::new(px.r, px.g, px.b)).collect())
+            .collect();
+
+        let color_samples = encode_av1_sequence(
+            &color_frames,
+            width,
+            height,
+            self.quantizer(self.quality),
+            self.speed,
+            self.color_space(),
+            self.threads,
+            keyframe_interval,
+            8,
+            TransferCharacteristics::Unspecified,
+            ColorPrimaries::Unspecified,
+        )?;
+
+        let color_byte_size = color_samples.iter().map(Vec::len).sum();
+        let durations: Vec<Duration> = frames.iter().map(|(_, d)| *d).collect();
+
+        let avif_file = crate::avif_sequence::serialize_sequence(
+            &color_samples,
+            width as u32,
+            height as u32,
+            &durations,
+            self.loop_count,
+        )
+        .map_err(|e| Error::Write(e.to_string()))?;
+
+        Ok(EncodedImage { avif_file, color_byte_size, alpha_byte_size: 0, quality: self.quality })
+    }
+}
+
+/// Decode `avif_file` back to RGB and compare it against the source with
+/// [`crate::ssim::ssim`], for the target-SSIM search.
+///
+/// The candidate almost always round-trips with an alpha plane (most input
+/// to this CLI is PNG), so every pixel format the decoder can hand back is
+/// accepted here and alpha is simply dropped for the comparison.
+fn decoded_ssim(source: ImgRef<RGBA8>, avif_file: &[u8]) -> Result<f64, Error> {
+    let decoded = avif_decode::Decoder::from_avif(avif_file)
+        .map_err(|e| Error::Encode(format!("decoding candidate for SSIM check: {e}")))?
+        .to_image()
+        .map_err(|e| Error::Encode(format!("decoding candidate for SSIM check: {e}")))?;
+    let narrow = |v: u16| (v >> 8) as u8;
+    let (width, height, decoded_rgb): (usize, usize, Vec<RGB8>) = match decoded {
+        avif_decode::Image::Rgb8(img) => (img.width(), img.height(), img.pixels().collect()),
+        avif_decode::Image::Rgba8(img) => {
+            (img.width(), img.height(), img.pixels().map(|px| RGB8::new(px.r, px.g, px.b)).collect())
+        }
+        avif_decode::Image::Rgb16(img) => (
+            img.width(),
+            img.height(),
+            img.pixels().map(|px| RGB8::new(narrow(px.r), narrow(px.g), narrow(px.b))).collect(),
+        ),
+        avif_decode::Image::Rgba16(img) => (
+            img.width(),
+            img.height(),
+            img.pixels().map(|px| RGB8::new(narrow(px.r), narrow(px.g), narrow(px.b))).collect(),
+        ),
+    };
+    let decoded = Img::new(decoded_rgb, width, height);
+    let source_rgb: Vec<RGB8> = source.pixels().map(|px| RGB8::new(px.r, px.g, px.b)).collect();
+    let source_rgb = Img::new(source_rgb, source.width(), source.height());
+    Ok(crate::ssim::ssim(source_rgb.as_ref(), decoded.as_ref()))
+}
+
+/// Rescale a full-range 16-bit sample (0..=65535) down to the sample range
+/// a given AV1 bit depth expects (e.g. 0..=1023 for 10-bit).
+fn scale_to_depth(v: u16, depth: u8) -> u16 {
+    let max = (1u32 << u32::from(depth)) - 1;
+    ((u32::from(v) * max + 32767) / 65535) as u16
+}
+
+fn quality_to_quantizer(quality: f32) -> usize {
+    let quality = quality.clamp(1., 100.) / 100.;
+    let q = 255. - quality.powf(1. / 2.) * 255.;
+    q.round() as usize
+}
+
+fn encode_to_av1<P: rav1e::Pixel + Default>(
+    pixels: &[P],
+    width: usize,
+    height: usize,
+    quantizer: usize,
+    speed: u8,
+    color_space: ColorSpace,
+    threads: Option<usize>,
+) -> Result<Vec<u8>, Error> {
+    encode_av1_sequence(
+        &[pixels.to_vec()],
+        width,
+        height,
+        quantizer,
+        speed,
+        color_space,
+        threads,
+        1,
+        8,
+        TransferCharacteristics::Unspecified,
+        ColorPrimaries::Unspecified,
+    )
+    .map(|mut frames| frames.pop().unwrap_or_default())
+}
+
+fn encode_to_av1_16<P: rav1e::Pixel + Default>(
+    pixels: &[P],
+    width: usize,
+    height: usize,
+    quantizer: usize,
+    speed: u8,
+    color_space: ColorSpace,
+    threads: Option<usize>,
+    bit_depth: u8,
+    transfer: TransferCharacteristics,
+    primaries: ColorPrimaries,
+) -> Result<Vec<u8>, Error> {
+    encode_av1_sequence(
+        &[pixels.to_vec()],
+        width,
+        height,
+        quantizer,
+        speed,
+        color_space,
+        threads,
+        1,
+        bit_depth,
+        transfer,
+        primaries,
+    )
+    .map(|mut frames| frames.pop().unwrap_or_default())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_av1_sequence<P: rav1e::Pixel + Default>(
+    frames: &[Vec<P>],
+    width: usize,
+    height: usize,
+    quantizer: usize,
+    speed: u8,
+    color_space: ColorSpace,
+    threads: Option<usize>,
+    keyframe_interval: u32,
+    bit_depth: u8,
+    transfer_characteristics: TransferCharacteristics,
+    color_primaries: ColorPrimaries,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let mut cfg = Config::new().with_encoder_config(EncoderConfig {
+        width,
+        height,
+        bit_depth: bit_depth.into(),
+        quantizer,
+        speed_settings: SpeedSettings::from_preset(speed.into()),
+        max_key_frame_interval: keyframe_interval.max(1).into(),
+        matrix_coefficients: match color_space {
+            ColorSpace::YCbCr => MatrixCoefficients::BT601,
+            ColorSpace::RGB => MatrixCoefficients::Identity,
+        },
+        // An identity matrix still gets subsampled like chroma unless told
+        // otherwise, which would silently average RGB's G/B planes over 2x2
+        // blocks; RGB (used for lossless and for the alpha plane) needs full
+        // 4:4:4 resolution and full-range samples to round-trip exactly.
+        chroma_sampling: match color_space {
+            ColorSpace::YCbCr => ChromaSampling::Cs420,
+            ColorSpace::RGB => ChromaSampling::Cs444,
+        },
+        color_range: match color_space {
+            ColorSpace::YCbCr => ColorRange::Limited,
+            ColorSpace::RGB => ColorRange::Full,
+        },
+        transfer_characteristics,
+        color_primaries,
+        ..Default::default()
+    });
+    if let Some(threads) = threads {
+        cfg = cfg.with_threads(threads);
+    }
+    let mut ctx: Context<P> = cfg.new_context().map_err(|e| Error::Encode(e.to_string()))?;
+
+    for pixels in frames {
+        let mut frame = ctx.new_frame();
+        frame.planes[0].copy_from_raw_u8(pixels, width, 1);
+        ctx.send_frame(frame).map_err(|e| Error::Encode(e.to_string()))?;
+    }
+    ctx.flush();
+
+    let mut packets = Vec::with_capacity(frames.len());
+    loop {
+        match ctx.receive_packet() {
+            Ok(packet) => packets.push(packet.data),
+            Err(EncoderStatus::LimitReached) => break,
+            Err(EncoderStatus::Encoded) => continue,
+            Err(e) => return Err(Error::Encode(e.to_string())),
+        }
+    }
+    Ok(packets)
+}
@@ -0,0 +1,293 @@
+//! Minimal muxer for animated AVIF: wraps a run of AV1 color samples (there
+//! is no alpha track) in the ISOBMFF boxes an "image sequence" brand needs
+//! (`ftyp`/`moov`/`mdat`), as opposed to the single-item `ftyp`/`meta`
+//! structure [`avif_serialize`] produces for stills.
+use std::time::Duration;
+
+const TIMESCALE: u32 = 1000; // milliseconds
+
+/// Build a complete animated `.avif` file from already-encoded AV1 samples.
+///
+/// `color_samples` carries one AV1 bitstream per frame; there is no alpha
+/// track (see [`crate::Encoder::encode_rgba_sequence`]). `durations` must
+/// have the same length as `color_samples`. `loop_count` is how many times
+/// the animation should repeat (0 = infinite), stored in a private
+/// `udta/LOOP` box: MIAF/ISOBMFF has no standard repeat-count box for image
+/// sequences, so this is a convention of this muxer, not something a generic
+/// player is required to honor.
+pub(crate) fn serialize_sequence(
+    color_samples: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    durations: &[Duration],
+    loop_count: u32,
+) -> Result<Vec<u8>, String> {
+    if color_samples.len() != durations.len() {
+        return Err("frame count doesn't match duration count".into());
+    }
+
+    let mut out = Vec::new();
+    write_box(&mut out, b"ftyp", &ftyp_body());
+
+    let (moov, stco_offset_pos) = moov_body(color_samples, width, height, durations, loop_count);
+    let moov_start = out.len();
+    write_box(&mut out, b"moov", &moov);
+
+    // `stco` chunk offsets are absolute from the start of the file. The mdat
+    // payload immediately follows moov, after its own 8-byte box header, so
+    // now that moov's final size is known the placeholder offset written by
+    // `moov_body` can be patched in place without re-building anything: the
+    // box's length doesn't change, only the 4 bytes of the offset value.
+    let mdat_offset = (out.len() + 8) as u32;
+    // moov's own box header (size+fourcc) is 8 bytes before its body starts.
+    let stco_field = moov_start + 8 + stco_offset_pos;
+    out[stco_field..stco_field + 4].copy_from_slice(&mdat_offset.to_be_bytes());
+
+    let mut mdat_body = Vec::new();
+    for samples in color_samples {
+        mdat_body.extend_from_slice(samples);
+    }
+    write_box(&mut out, b"mdat", &mdat_body);
+    Ok(out)
+}
+
+fn ftyp_body() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"avis"); // major brand: AVIF image sequence
+    body.extend_from_slice(&0u32.to_be_bytes()); // minor version
+    body.extend_from_slice(b"avis");
+    body.extend_from_slice(b"msf1");
+    body.extend_from_slice(b"iso8");
+    body
+}
+
+/// Builds the `moov` box body. Returns the byte offset, *within that body*,
+/// of the `stco` entry's chunk-offset field, so the caller can patch in the
+/// real absolute file offset once `moov`'s own size is known.
+fn moov_body(
+    samples: &[Vec<u8>],
+    width: u32,
+    height: u32,
+    durations: &[Duration],
+    loop_count: u32,
+) -> (Vec<u8>, usize) {
+    let total_duration: u32 = durations.iter().map(|d| d.as_millis() as u32).sum();
+
+    let mut mvhd = Vec::new();
+    mvhd.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    mvhd.extend_from_slice(&0u32.to_be_bytes()); // creation time
+    mvhd.extend_from_slice(&0u32.to_be_bytes()); // modification time
+    mvhd.extend_from_slice(&TIMESCALE.to_be_bytes());
+    mvhd.extend_from_slice(&total_duration.to_be_bytes());
+    mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate, 1.0
+    mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume, 1.0
+    mvhd.extend_from_slice(&0u16.to_be_bytes()); // reserved
+    mvhd.extend_from_slice(&[0u8; 8]); // reserved[2]
+    mvhd.extend_from_slice(&unity_matrix());
+    mvhd.extend_from_slice(&[0u8; 24]); // pre_defined[6]
+    mvhd.extend_from_slice(&2u32.to_be_bytes()); // next_track_ID
+
+    let (trak, stco_pos_in_trak) = trak_body(samples, width, height, durations);
+
+    let mut moov = Vec::new();
+    write_box(&mut moov, b"mvhd", &mvhd);
+    let trak_start = moov.len();
+    write_box(&mut moov, b"trak", &trak);
+    write_box(&mut moov, b"udta", &udta_body(loop_count));
+
+    // trak's own box header (size+fourcc) is 8 bytes before its body starts.
+    (moov, trak_start + 8 + stco_pos_in_trak)
+}
+
+/// Private `udta/LOOP` box carrying the requested repeat count.
+fn udta_body(loop_count: u32) -> Vec<u8> {
+    let mut udta = Vec::new();
+    write_box(&mut udta, b"LOOP", &loop_count.to_be_bytes());
+    udta
+}
+
+fn unity_matrix() -> [u8; 36] {
+    let mut m = [0u8; 36];
+    m[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    m[16..20].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    m[32..36].copy_from_slice(&0x4000_0000u32.to_be_bytes());
+    m
+}
+
+/// Returns the `trak` body, plus the offset (within that body) of the
+/// `stco` entry's chunk-offset field.
+fn trak_body(samples: &[Vec<u8>], width: u32, height: u32, durations: &[Duration]) -> (Vec<u8>, usize) {
+    let total_duration: u32 = durations.iter().map(|d| d.as_millis() as u32).sum();
+
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&0x0000_0007u32.to_be_bytes()); // version/flags: track enabled, in movie, in preview
+    tkhd.extend_from_slice(&0u32.to_be_bytes()); // creation time
+    tkhd.extend_from_slice(&0u32.to_be_bytes()); // modification time
+    tkhd.extend_from_slice(&1u32.to_be_bytes()); // track_ID
+    tkhd.extend_from_slice(&0u32.to_be_bytes()); // reserved
+    tkhd.extend_from_slice(&total_duration.to_be_bytes());
+    tkhd.extend_from_slice(&[0u8; 8]); // reserved[2]
+    tkhd.extend_from_slice(&0i16.to_be_bytes()); // layer
+    tkhd.extend_from_slice(&0i16.to_be_bytes()); // alternate_group
+    tkhd.extend_from_slice(&0i16.to_be_bytes()); // volume (0, not an audio track)
+    tkhd.extend_from_slice(&0u16.to_be_bytes()); // reserved
+    tkhd.extend_from_slice(&unity_matrix());
+    tkhd.extend_from_slice(&(width << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(height << 16).to_be_bytes());
+
+    let (mdia, stco_pos_in_mdia) = mdia_body(samples, width, height, durations);
+
+    let mut trak = Vec::new();
+    write_box(&mut trak, b"tkhd", &tkhd);
+    let mdia_start = trak.len();
+    write_box(&mut trak, b"mdia", &mdia);
+
+    (trak, mdia_start + 8 + stco_pos_in_mdia)
+}
+
+/// Returns the `mdia` body, plus the offset (within that body) of the
+/// `stco` entry's chunk-offset field.
+fn mdia_body(samples: &[Vec<u8>], width: u32, height: u32, durations: &[Duration]) -> (Vec<u8>, usize) {
+    let total_duration: u32 = durations.iter().map(|d| d.as_millis() as u32).sum();
+
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    mdhd.extend_from_slice(&0u32.to_be_bytes()); // creation time
+    mdhd.extend_from_slice(&0u32.to_be_bytes()); // modification time
+    mdhd.extend_from_slice(&TIMESCALE.to_be_bytes());
+    mdhd.extend_from_slice(&total_duration.to_be_bytes());
+    mdhd.extend_from_slice(&0x55C4u16.to_be_bytes()); // language: "und"
+    mdhd.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    hdlr.extend_from_slice(&0u32.to_be_bytes()); // pre_defined
+    hdlr.extend_from_slice(b"pict"); // handler type: image sequence, per MIAF
+    hdlr.extend_from_slice(&[0u8; 12]); // reserved[3]
+    hdlr.extend_from_slice(b"cavif-rs image sequence\0");
+
+    let (minf, stco_pos_in_minf) = minf_body(samples, width, height, durations);
+
+    let mut mdia = Vec::new();
+    write_box(&mut mdia, b"mdhd", &mdhd);
+    write_box(&mut mdia, b"hdlr", &hdlr);
+    let minf_start = mdia.len();
+    write_box(&mut mdia, b"minf", &minf);
+
+    (mdia, minf_start + 8 + stco_pos_in_minf)
+}
+
+/// Returns the `minf` body, plus the offset (within that body) of the
+/// `stco` entry's chunk-offset field.
+fn minf_body(samples: &[Vec<u8>], width: u32, height: u32, durations: &[Duration]) -> (Vec<u8>, usize) {
+    let mut vmhd = Vec::new();
+    vmhd.extend_from_slice(&1u32.to_be_bytes()); // version/flags (flags=1, required)
+    vmhd.extend_from_slice(&[0u8; 8]); // graphicsmode + opcolor[3]
+
+    let mut url = Vec::new();
+    url.extend_from_slice(&1u32.to_be_bytes()); // version/flags: self-contained, no data
+
+    let mut dref = Vec::new();
+    dref.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    write_box(&mut dref, b"url ", &url);
+
+    let mut dinf = Vec::new();
+    write_box(&mut dinf, b"dref", &dref);
+
+    let (stbl, stco_pos_in_stbl) = stbl_body(samples, width, height, durations);
+
+    let mut minf = Vec::new();
+    write_box(&mut minf, b"vmhd", &vmhd);
+    write_box(&mut minf, b"dinf", &dinf);
+    let stbl_start = minf.len();
+    write_box(&mut minf, b"stbl", &stbl);
+
+    (minf, stbl_start + 8 + stco_pos_in_stbl)
+}
+
+/// Returns the `stbl` body, plus the offset (within that body) of the
+/// `stco` entry's chunk-offset field.
+fn stbl_body(samples: &[Vec<u8>], width: u32, height: u32, durations: &[Duration]) -> (Vec<u8>, usize) {
+    let mut stsd = Vec::new();
+    stsd.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    write_box(&mut stsd, b"av01", &av01_sample_entry_body(width, height));
+
+    let mut stts = Vec::new();
+    stts.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    for d in durations {
+        stts.extend_from_slice(&1u32.to_be_bytes()); // sample_count
+        stts.extend_from_slice(&(d.as_millis() as u32).to_be_bytes()); // sample_delta
+    }
+
+    let mut stsz = Vec::new();
+    stsz.extend_from_slice(&0u32.to_be_bytes()); // sample_size (0 = sizes in table below)
+    stsz.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    for s in samples {
+        stsz.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    }
+
+    // One chunk holding every sample contiguously, in order.
+    let mut stsc = Vec::new();
+    stsc.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    stsc.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    stsc.extend_from_slice(&1u32.to_be_bytes()); // first_chunk
+    stsc.extend_from_slice(&(samples.len() as u32).to_be_bytes()); // samples_per_chunk
+    stsc.extend_from_slice(&1u32.to_be_bytes()); // sample_description_index
+
+    let mut stco = Vec::new();
+    stco.extend_from_slice(&0u32.to_be_bytes()); // version/flags
+    stco.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+    let stco_offset_field = stco.len();
+    stco.extend_from_slice(&0u32.to_be_bytes()); // chunk_offset placeholder, patched by caller
+
+    let mut stbl = Vec::new();
+    write_box(&mut stbl, b"stsd", &stsd);
+    write_box(&mut stbl, b"stts", &stts);
+    write_box(&mut stbl, b"stsc", &stsc);
+    write_box(&mut stbl, b"stsz", &stsz);
+    let stco_start = stbl.len();
+    write_box(&mut stbl, b"stco", &stco);
+
+    (stbl, stco_start + 8 + stco_offset_field)
+}
+
+/// `VisualSampleEntry` for `av01`, per the AV1 Codec ISOBMFF Binding spec.
+fn av01_sample_entry_body(width: u32, height: u32) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&[0u8; 6]); // reserved
+    body.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+    body.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+    body.extend_from_slice(&0u16.to_be_bytes()); // reserved
+    body.extend_from_slice(&[0u8; 12]); // pre_defined[3]
+    body.extend_from_slice(&(width as u16).to_be_bytes());
+    body.extend_from_slice(&(height as u16).to_be_bytes());
+    body.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // horizresolution, 72 dpi
+    body.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // vertresolution, 72 dpi
+    body.extend_from_slice(&0u32.to_be_bytes()); // reserved
+    body.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+    body.extend_from_slice(&[0u8; 32]); // compressorname
+    body.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // pre_defined
+    write_box(&mut body, b"av1C", &av1c_body());
+    body
+}
+
+/// `AV1CodecConfigurationBox`: the samples this muxer produces are always
+/// 8-bit 4:2:0, encoded with `encode_av1_sequence`'s fixed defaults.
+fn av1c_body() -> Vec<u8> {
+    vec![
+        0x81, // marker=1, version=1
+        0x00, // seq_profile=0, seq_level_idx_0=0
+        0x0C, // seq_tier_0=0, high_bitdepth=0, twelve_bit=0, monochrome=0, subsampling_x=1, subsampling_y=1, chroma_sample_position=0
+        0x00, // reserved, initial_presentation_delay_present=0, reserved
+    ]
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    let size = (body.len() + 8) as u32;
+    out.extend_from_slice(&size.to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+}
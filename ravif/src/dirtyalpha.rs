@@ -0,0 +1,47 @@
+use imgref::ImgRef;
+use rgb::RGBA8;
+
+/// Blur the RGB channels under fully-transparent pixels so that lossy
+/// compression doesn't have to spend bits on noise nobody will see, while
+/// keeping visually-similar edges next to opaque pixels untouched.
+///
+/// Returns `None` when the image has no transparent pixels worth touching.
+pub(crate) fn blurred_dirty_alpha(img: ImgRef<RGBA8>) -> Option<Vec<RGBA8>> {
+    if !img.pixels().any(|px| px.a == 0) {
+        return None;
+    }
+
+    let width = img.width();
+    let height = img.height();
+    let mut out: Vec<RGBA8> = img.pixels().collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = y * width + x;
+            if out[idx].a != 0 {
+                continue;
+            }
+            let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+            for dy in -1i32..=1 {
+                for dx in -1i32..=1 {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    let neighbor = img[(nx as usize, ny as usize)];
+                    if neighbor.a == 0 {
+                        continue;
+                    }
+                    r += u32::from(neighbor.r);
+                    g += u32::from(neighbor.g);
+                    b += u32::from(neighbor.b);
+                    n += 1;
+                }
+            }
+            if n > 0 {
+                out[idx] = RGBA8::new((r / n) as u8, (g / n) as u8, (b / n) as u8, 0);
+            }
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,28 @@
+use std::fmt::{self, Display};
+
+/// Type-erased error, used by the CLI to print a chain of causes.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Failure cases that can happen while encoding an image.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Image has zero width or height
+    TooSmall,
+    /// rav1e refused to encode the frame
+    Encode(String),
+    /// The AVIF/HEIF container could not be assembled
+    Write(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooSmall => write!(f, "image is too small to encode"),
+            Self::Encode(msg) => write!(f, "rav1e encoding failed: {msg}"),
+            Self::Write(msg) => write!(f, "failed to write AVIF container: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
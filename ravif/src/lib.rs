@@ -9,17 +9,21 @@
 //! # Ok(()) }
 
 mod av1encoder;
+mod avif_sequence;
+mod ssim;
 
 mod error;
 pub use av1encoder::AlphaColorMode;
 pub use av1encoder::ColorSpace;
 pub use av1encoder::EncodedImage;
 pub use av1encoder::Encoder;
+pub use av1encoder::HdrTransferCharacteristic;
 pub use error::BoxError;
 pub use error::Error;
 
 pub mod load_rgba;
 pub use load_rgba::load_rgba;
+pub use load_rgba::load_rgba_sequence;
 
 #[doc(inline)]
 pub use rav1e::prelude::MatrixCoefficients;
@@ -29,4 +33,4 @@ mod dirtyalpha;
 #[doc(no_inline)]
 pub use imgref::Img;
 #[doc(no_inline)]
-pub use rgb::{RGB8, RGBA8};
+pub use rgb::{RGB8, RGBA16, RGBA8};
@@ -0,0 +1,255 @@
+use crate::error::BoxError;
+use imgref::Img;
+use rgb::RGBA8;
+use std::time::Duration;
+
+/// Decode a PNG, JPEG, or (with the `raw` feature) camera RAW file into RGBA pixels.
+///
+/// For animated PNGs only the first frame is returned; use
+/// [`load_rgba_sequence`] to get every frame of an animation.
+pub fn load_rgba(data: &[u8], premultiplied_alpha: bool) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    if data.starts_with(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']) {
+        load_png(data, premultiplied_alpha)
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        load_jpeg(data)
+    } else if is_raw(data) {
+        load_raw(data)
+    } else if is_heif(data) {
+        load_heif(data)
+    } else {
+        Err("unsupported file: not a PNG, JPEG, RAW, or HEIF image".into())
+    }
+}
+
+fn is_heif(data: &[u8]) -> bool {
+    data.len() >= 12
+        && &data[4..8] == b"ftyp"
+        && matches!(&data[8..12], b"heic" | b"heix" | b"hevc" | b"heim" | b"heis" | b"mif1")
+}
+
+#[cfg(feature = "heif")]
+fn load_heif(data: &[u8]) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    use libheif_rs::{ColorSpace, HeifContext, RgbChroma};
+
+    let ctx = HeifContext::read_from_bytes(data).map_err(|e| format!("unable to open HEIF file: {e}"))?;
+    let handle = ctx.primary_image_handle().map_err(|e| format!("no primary HEIF image: {e}"))?;
+    let image = handle
+        .decode(ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(|e| format!("unable to decode HEIF image: {e}"))?;
+    let plane = image.planes().interleaved.ok_or("HEIF image has no interleaved RGBA plane")?;
+
+    let width = plane.width as usize;
+    let height = plane.height as usize;
+    let stride = plane.stride;
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in plane.data.chunks(stride) {
+        for px in row[..width * 4].chunks_exact(4) {
+            pixels.push(RGBA8::new(px[0], px[1], px[2], px[3]));
+        }
+    }
+    Ok(Img::new(pixels, width, height))
+}
+
+#[cfg(not(feature = "heif"))]
+fn load_heif(_data: &[u8]) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    Err("HEIF/HEIC input support requires building cavif with the `heif` feature".into())
+}
+
+/// Whether the file's extension is one of the camera RAW formats handled
+/// by [`load_rgba`] when the `raw` feature is enabled.
+pub fn is_raw_extension(ext: &std::ffi::OsStr) -> bool {
+    ["cr2", "nef", "arw", "dng", "rw2", "orf", "raf"]
+        .iter()
+        .any(|raw_ext| ext.eq_ignore_ascii_case(raw_ext))
+}
+
+fn is_raw(data: &[u8]) -> bool {
+    // TIFF-based RAW formats (CR2, NEF, ARW, DNG, RW2, ORF) start with a TIFF
+    // byte-order marker; RW2 and ORF follow it with their own magic number
+    // instead of TIFF's 42. RAF (Fujifilm) has its own "FUJIFILMCCD-RAW" magic.
+    data.starts_with(b"II*\0")
+        || data.starts_with(b"MM\0*")
+        || data.starts_with(b"IIU\0")
+        || data.starts_with(b"IIRO")
+        || data.starts_with(b"IIRS")
+        || data.starts_with(b"MMOR")
+        || data.starts_with(b"FUJIFILMCCD-RAW")
+}
+
+#[cfg(feature = "raw")]
+fn load_raw(data: &[u8]) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    use imagepipe::{ImageSource, Pipeline};
+
+    let decoded = rawloader::decode(&mut std::io::Cursor::new(data))
+        .map_err(|e| format!("unable to decode RAW file: {e}"))?;
+    let mut pipeline = Pipeline::new_from_source(ImageSource::Raw(decoded))
+        .map_err(|e| format!("unable to set up RAW development pipeline: {e}"))?;
+    pipeline.run(None);
+    let image = pipeline
+        .output_8bit(None)
+        .map_err(|e| format!("unable to develop RAW file to sRGB: {e}"))?;
+
+    let pixels: Vec<RGBA8> = image
+        .data
+        .chunks_exact(3)
+        .map(|px| RGBA8::new(px[0], px[1], px[2], 255))
+        .collect();
+    Ok(Img::new(pixels, image.width, image.height))
+}
+
+#[cfg(not(feature = "raw"))]
+fn load_raw(_data: &[u8]) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    Err("RAW input support requires building cavif with the `raw` feature".into())
+}
+
+/// Decode every frame of an animated GIF or APNG, together with how long
+/// each frame should be shown for.
+///
+/// Returns `Ok(None)` for a PNG without animation control and for any
+/// other non-GIF input, so callers can fall back to the regular still-image
+/// path. Every GIF, even a single-frame one, is returned as a sequence.
+pub fn load_rgba_sequence(
+    data: &[u8],
+    premultiplied_alpha: bool,
+) -> Result<Option<Vec<(Img<Vec<RGBA8>>, Duration)>>, BoxError> {
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Ok(Some(load_gif_frames(data)?));
+    }
+    if data.starts_with(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']) {
+        if let Some(frames) = load_apng_frames(data, premultiplied_alpha)? {
+            return Ok(Some(frames));
+        }
+    }
+    Ok(None)
+}
+
+fn load_png(data: &[u8], premultiplied_alpha: bool) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::ALPHA | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let pixels: Vec<RGBA8> = buf[..info.buffer_size()]
+        .chunks_exact(4)
+        .map(|px| RGBA8::new(px[0], px[1], px[2], px[3]))
+        .collect();
+    let img = Img::new(pixels, info.width as usize, info.height as usize);
+    Ok(if premultiplied_alpha { premultiply(img) } else { img })
+}
+
+fn load_jpeg(data: &[u8]) -> Result<Img<Vec<RGBA8>>, BoxError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder.decode()?;
+    let info = decoder.info().ok_or("no JPEG header")?;
+    let rgba: Vec<RGBA8> = pixels
+        .chunks_exact(3)
+        .map(|px| RGBA8::new(px[0], px[1], px[2], 255))
+        .collect();
+    Ok(Img::new(rgba, info.width as usize, info.height as usize))
+}
+
+/// Decode every frame of an animated GIF onto the logical screen, compositing
+/// each frame's (possibly smaller, possibly offset) sub-rectangle according to
+/// its disposal method, since `gif::Frame::buffer` is only ever sized to that
+/// frame's own rectangle, not the full canvas.
+fn load_gif_frames(data: &[u8]) -> Result<Vec<(Img<Vec<RGBA8>>, Duration)>, BoxError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(data)?;
+    let width = decoder.width() as usize;
+    let height = decoder.height() as usize;
+
+    let mut canvas = vec![RGBA8::new(0, 0, 0, 0); width * height];
+    let mut previous_disposal = gif::DisposalMethod::Keep;
+    let mut previous_rect = (0, 0, 0, 0);
+    let mut previous_snapshot: Option<Vec<RGBA8>> = None;
+
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        match previous_disposal {
+            gif::DisposalMethod::Background => clear_rect(&mut canvas, width, previous_rect),
+            gif::DisposalMethod::Previous => {
+                if let Some(snapshot) = previous_snapshot.take() {
+                    canvas = snapshot;
+                }
+            }
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => {}
+        }
+
+        let rect = (frame.left as usize, frame.top as usize, frame.width as usize, frame.height as usize);
+        if frame.dispose == gif::DisposalMethod::Previous {
+            previous_snapshot = Some(canvas.clone());
+        }
+        composite_frame_rect(&mut canvas, width, &frame.buffer, rect);
+
+        previous_disposal = frame.dispose;
+        previous_rect = rect;
+
+        let delay = Duration::from_millis(u64::from(frame.delay.max(1)) * 10);
+        frames.push((Img::new(canvas.clone(), width, height), delay));
+    }
+    Ok(frames)
+}
+
+fn clear_rect(canvas: &mut [RGBA8], canvas_width: usize, (left, top, w, h): (usize, usize, usize, usize)) {
+    for y in top..top + h {
+        let row = y * canvas_width + left;
+        canvas[row..row + w].fill(RGBA8::new(0, 0, 0, 0));
+    }
+}
+
+/// Draw a frame's local RGBA buffer onto `canvas` at `(left, top)`, skipping
+/// transparent pixels (GIF has no partial alpha) so whatever the canvas
+/// already held there shows through.
+fn composite_frame_rect(
+    canvas: &mut [RGBA8],
+    canvas_width: usize,
+    frame_buffer: &[u8],
+    (left, top, w, h): (usize, usize, usize, usize),
+) {
+    for (y, row) in frame_buffer.chunks_exact(w * 4).take(h).enumerate() {
+        let dest_row = (top + y) * canvas_width + left;
+        for (src, dest) in row.chunks_exact(4).zip(&mut canvas[dest_row..dest_row + w]) {
+            if src[3] != 0 {
+                *dest = RGBA8::new(src[0], src[1], src[2], src[3]);
+            }
+        }
+    }
+}
+
+fn load_apng_frames(
+    data: &[u8],
+    premultiplied_alpha: bool,
+) -> Result<Option<Vec<(Img<Vec<RGBA8>>, Duration)>>, BoxError> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::ALPHA | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    if !reader.info().animation_control().is_some() {
+        return Ok(None);
+    }
+    let mut frames = Vec::new();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    while let Ok(info) = reader.next_frame(&mut buf) {
+        let pixels: Vec<RGBA8> = buf[..info.buffer_size()]
+            .chunks_exact(4)
+            .map(|px| RGBA8::new(px[0], px[1], px[2], px[3]))
+            .collect();
+        let img = Img::new(pixels, info.width as usize, info.height as usize);
+        let img = if premultiplied_alpha { premultiply(img) } else { img };
+        let frame_control = reader.info().frame_control().cloned();
+        let delay = frame_control
+            .map(|fc| Duration::from_secs_f64(f64::from(fc.delay_num) / f64::from(fc.delay_den.max(1))))
+            .unwrap_or(Duration::from_millis(100));
+        frames.push((img, delay));
+    }
+    Ok(Some(frames))
+}
+
+fn premultiply(mut img: Img<Vec<RGBA8>>) -> Img<Vec<RGBA8>> {
+    for px in img.buf_mut() {
+        px.r = (u16::from(px.r) * u16::from(px.a) / 255) as u8;
+        px.g = (u16::from(px.g) * u16::from(px.a) / 255) as u8;
+        px.b = (u16::from(px.b) * u16::from(px.a) / 255) as u8;
+    }
+    img
+}
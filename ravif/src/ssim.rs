@@ -0,0 +1,79 @@
+use imgref::ImgRef;
+use rgb::RGB8;
+
+const WINDOW: usize = 8;
+const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+
+/// Windowed SSIM between two equally-sized images, computed on linear-light
+/// luma over non-overlapping 8x8 blocks and averaged into `[0, 1]`.
+///
+/// Used by the target-size/target-quality search to judge how close a
+/// candidate encode is to the source.
+pub(crate) fn ssim(a: ImgRef<RGB8>, b: ImgRef<RGB8>) -> f64 {
+    debug_assert_eq!(a.width(), b.width());
+    debug_assert_eq!(a.height(), b.height());
+    let (width, height) = (a.width(), b.height().min(a.height()));
+    let luma_a = to_linear_luma(a);
+    let luma_b = to_linear_luma(b);
+
+    let mut total = 0.;
+    let mut blocks = 0;
+    let mut y = 0;
+    while y < height {
+        let mut x = 0;
+        while x < width {
+            total += block_ssim(&luma_a, &luma_b, width, height, x, y);
+            blocks += 1;
+            x += WINDOW;
+        }
+        y += WINDOW;
+    }
+    if blocks == 0 { 1. } else { total / f64::from(blocks) }
+}
+
+fn block_ssim(a: &[f64], b: &[f64], width: usize, height: usize, x0: usize, y0: usize) -> f64 {
+    let mut n = 0usize;
+    let (mut sum_a, mut sum_b) = (0., 0.);
+    for y in y0..(y0 + WINDOW).min(height) {
+        for x in x0..(x0 + WINDOW).min(width) {
+            sum_a += a[y * width + x];
+            sum_b += b[y * width + x];
+            n += 1;
+        }
+    }
+    if n == 0 {
+        return 1.;
+    }
+    let (mean_a, mean_b) = (sum_a / n as f64, sum_b / n as f64);
+
+    let (mut var_a, mut var_b, mut covar) = (0., 0., 0.);
+    for y in y0..(y0 + WINDOW).min(height) {
+        for x in x0..(x0 + WINDOW).min(width) {
+            let da = a[y * width + x] - mean_a;
+            let db = b[y * width + x] - mean_b;
+            var_a += da * da;
+            var_b += db * db;
+            covar += da * db;
+        }
+    }
+    var_a /= n as f64;
+    var_b /= n as f64;
+    covar /= n as f64;
+
+    ((2. * mean_a * mean_b + C1) * (2. * covar + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+fn to_linear_luma(img: ImgRef<RGB8>) -> Vec<f64> {
+    img.pixels()
+        .map(|px| {
+            let srgb_to_linear = |c: u8| {
+                let c = f64::from(c) / 255.;
+                if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+            };
+            let (r, g, b) = (srgb_to_linear(px.r), srgb_to_linear(px.g), srgb_to_linear(px.b));
+            (0.2126 * r + 0.7152 * g + 0.0722 * b) * 255.
+        })
+        .collect()
+}
@@ -1,11 +1,15 @@
 use clap::{AppSettings, Arg, Command};
-use ravif::{load_rgba, AlphaColorMode, BoxError, ColorSpace, EncodedImage, Encoder};
+use ravif::{
+    load_rgba, load_rgba_sequence, AlphaColorMode, BoxError, ColorSpace, EncodedImage, Encoder,
+    HdrTransferCharacteristic,
+};
 use rayon::prelude::*;
 use std::fs;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 fn main() {
     if let Err(e) = run() {
@@ -24,6 +28,56 @@ enum MaybePath {
     Path(PathBuf),
 }
 
+const CONVERTIBLE_EXTENSIONS: &[&str] = &[
+    "png",
+    "jpg",
+    "jpeg",
+    #[cfg(feature = "heif")]
+    "heic",
+    #[cfg(feature = "heif")]
+    "heif",
+];
+
+/// Whether `ext` is one of the extensions collected by `--recursive`: the
+/// fixed list above, plus (when built with the `raw` feature) whatever
+/// `ravif::load_rgba::is_raw_extension` recognizes as a camera RAW format —
+/// kept as the single source of truth so the two lists can't drift apart.
+/// GIF is not in the list, although `load_rgba_sequence` can read it.
+fn is_convertible_extension(ext: &std::ffi::OsStr) -> bool {
+    CONVERTIBLE_EXTENSIONS.iter().any(|c| ext.eq_ignore_ascii_case(c))
+        || is_raw_extension_if_enabled(ext)
+}
+
+#[cfg(feature = "raw")]
+fn is_raw_extension_if_enabled(ext: &std::ffi::OsStr) -> bool {
+    ravif::load_rgba::is_raw_extension(ext)
+}
+
+#[cfg(not(feature = "raw"))]
+fn is_raw_extension_if_enabled(_ext: &std::ffi::OsStr) -> bool {
+    false
+}
+
+/// Widen an 8-bit channel to 16-bit by replicating the byte, so an 8-bit
+/// source can still go through the HDR 10/12-bit encode path.
+fn widen(c: u8) -> u16 {
+    (u16::from(c) << 8) | u16::from(c)
+}
+
+/// Walk `dir` collecting every file whose extension is convertible and that
+/// doesn't match any of `excludes`, mirroring the directory structure so the
+/// caller can reconstruct output paths under `use_dir`.
+fn collect_convertible_files(dir: &Path, excludes: &[glob::Pattern]) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(walkdir::DirEntry::into_path)
+        .filter(|path| path.extension().is_some_and(is_convertible_extension))
+        .filter(|path| !excludes.iter().any(|pattern| pattern.matches_path(path)))
+        .collect()
+}
+
 fn run() -> Result<(), BoxError> {
     let args = Command::new("cavif-rs")
         .version(clap::crate_version!())
@@ -70,17 +124,71 @@ fn run() -> Result<(), BoxError> {
         .arg(Arg::new("dirty-alpha")
             .long("dirty-alpha")
             .help("Keep RGB data of fully-transparent pixels (makes larger, lower quality files)"))
+        .arg(Arg::new("lossless")
+            .long("lossless")
+            .help("Mathematically lossless encoding. Overrides --quality and --color"))
+        .arg(Arg::new("max-size")
+            .long("max-size")
+            .value_name("bytes")
+            .takes_value(true)
+            .help("Automatically pick the highest quality whose encoded file is at most this many bytes, instead of a fixed -Q"))
+        .arg(Arg::new("target-ssim")
+            .long("target-ssim")
+            .value_name("0..1")
+            .takes_value(true)
+            .conflicts_with("max-size")
+            .help("Automatically pick the lowest quality whose decoded SSIM is at least this, instead of a fixed -Q"))
         .arg(Arg::new("color")
             .long("color")
             .default_value("ycbcr")
             .takes_value(true)
             .possible_values(["ycbcr", "rgb"])
             .help("Internal AVIF color space. YCbCr works better for human eyes."))
+        .arg(Arg::new("fps")
+            .long("fps")
+            .value_name("n")
+            .default_value("10")
+            .takes_value(true)
+            .help("Fallback frame rate for animated inputs without per-frame timing"))
+        .arg(Arg::new("loop")
+            .long("loop")
+            .value_name("n")
+            .default_value("0")
+            .takes_value(true)
+            .help("Number of times to repeat the animation (0 = infinite)"))
+        .arg(Arg::new("keyframe-interval")
+            .long("keyframe-interval")
+            .value_name("n")
+            .takes_value(true)
+            .help("Frames between keyframes for animated input. Defaults to all-intra for short clips and a 30-frame GOP (letting rav1e place inter frames) for longer ones"))
+        .arg(Arg::new("depth")
+            .long("depth")
+            .value_name("n")
+            .default_value("8")
+            .takes_value(true)
+            .possible_values(["8", "10", "12"])
+            .help("Output bit depth. 10/12-bit enables the HDR encoding path"))
+        .arg(Arg::new("hdr")
+            .long("hdr")
+            .value_name("transfer")
+            .takes_value(true)
+            .possible_values(["pq", "hlg"])
+            .help("HDR transfer characteristics to tag 10/12-bit output with (implies BT.2020 primaries)"))
+        .arg(Arg::new("recursive")
+            .short('r')
+            .long("recursive")
+            .help("Treat IMAGES entries as directories and convert every supported file found inside them"))
+        .arg(Arg::new("exclude")
+            .long("exclude")
+            .value_name("glob")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .help("Skip paths matching this glob when using --recursive. Can be repeated"))
         .arg(Arg::new("IMAGES")
             .index(1)
             .allow_invalid_utf8(true)
             .min_values(1)
-            .help("One or more JPEG or PNG files to convert. \"-\" is interpreted as stdin/stdout.")
+            .help("One or more JPEG/PNG files (or, with --recursive, directories) to convert. \"-\" is interpreted as stdin/stdout.")
             .multiple_occurrences(true))
         .get_matches();
 
@@ -95,17 +203,62 @@ fn run() -> Result<(), BoxError> {
     let quiet = args.is_present("quiet");
     let threads = args.value_of_t::<usize>("threads")?;
     let dirty_alpha = args.is_present("dirty-alpha");
+    let lossless = args.is_present("lossless");
+    let max_size = args.value_of("max-size").map(str::parse::<usize>).transpose()?;
+    let target_ssim = args.value_of("target-ssim").map(str::parse::<f32>).transpose()?;
+    let fallback_fps = args.value_of_t::<f32>("fps")?;
+    let loop_count = args.value_of_t::<u32>("loop")?;
+    let keyframe_interval = args.value_of("keyframe-interval").map(str::parse::<u32>).transpose()?;
+    let hdr_transfer = match args.value_of("hdr") {
+        Some("pq") => Some(HdrTransferCharacteristic::Pq),
+        Some("hlg") => Some(HdrTransferCharacteristic::Hlg),
+        Some(x) => Err(format!("bad HDR transfer characteristic: {x}"))?,
+        None => None,
+    };
+    let mut depth = args.value_of_t::<u8>("depth")?;
+    if hdr_transfer.is_some() && depth == 8 {
+        // --hdr only makes sense at 10/12-bit, so a depth of 8 (the default, or given explicitly) is raised to 10.
+        depth = 10;
+    }
+    let recursive = args.is_present("recursive");
+    let excludes: Vec<glob::Pattern> = args
+        .values_of("exclude")
+        .into_iter()
+        .flatten()
+        .map(glob::Pattern::new)
+        .collect::<Result<_, _>>()?;
 
     let color_space = match args.value_of("color").expect("default") {
         "ycbcr" => ColorSpace::YCbCr,
         "rgb" => ColorSpace::RGB,
         x => Err(format!("bad color type: {x}"))?,
     };
-    let files = args
+    let inputs = args
         .values_of_os("IMAGES")
         .ok_or("Please specify image paths to convert")?;
-    let files: Vec<_> = files
-        .filter(|pathstr| {
+    // The path relative to the directory that was walked to find it, used
+    // under `--recursive` to mirror the input tree's structure under the
+    // output directory instead of flattening every file to its basename.
+    let inputs: Vec<(PathBuf, Option<PathBuf>)> = if recursive {
+        let mut expanded = Vec::new();
+        for input in inputs {
+            let path = Path::new(input);
+            if path.is_dir() {
+                for file in collect_convertible_files(path, &excludes) {
+                    let relative = file.strip_prefix(path).unwrap_or(&file).to_path_buf();
+                    expanded.push((file, Some(relative)));
+                }
+            } else {
+                expanded.push((path.to_path_buf(), None));
+            }
+        }
+        expanded
+    } else {
+        inputs.map(|s| (PathBuf::from(s), None)).collect()
+    };
+    let files: Vec<_> = inputs
+        .into_iter()
+        .filter(|(pathstr, _)| {
             let path = Path::new(&pathstr);
             if let Some(s) = path.to_str() {
                 if quiet && s.parse::<u8>().is_ok() && !path.exists() {
@@ -126,11 +279,7 @@ fn run() -> Result<(), BoxError> {
                 true
             })
         })
-        .map(|p| if p == "-" {
-            MaybePath::Stdio
-        } else {
-            MaybePath::Path(PathBuf::from(p))
-        })
+        .map(|(p, relative)| (if p.to_str() == Some("-") { MaybePath::Stdio } else { MaybePath::Path(p) }, relative))
         .collect();
 
     if files.is_empty() {
@@ -147,14 +296,20 @@ fn run() -> Result<(), BoxError> {
         _ => false,
     };
 
-    let process = move |data: Vec<u8>, input_path: &MaybePath| -> Result<(), BoxError> {
-        let img = load_rgba(&data, false)?;
+    let process = move |data: Vec<u8>, input_path: &MaybePath, relative: Option<&Path>| -> Result<(), BoxError> {
+        let sequence = load_rgba_sequence(&data, false)?;
+        let still = if sequence.is_none() { Some(load_rgba(&data, false)?) } else { None };
         drop(data);
         let out_path = match (&output, input_path) {
             (None, MaybePath::Path(input)) => MaybePath::Path(input.with_extension("avif")),
             (Some(MaybePath::Path(output)), MaybePath::Path(ref input)) => MaybePath::Path({
                 if use_dir {
-                    output.join(Path::new(input.file_name().unwrap()).with_extension("avif"))
+                    let rel = relative.map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from(input.file_name().unwrap()));
+                    let dest = output.join(rel).with_extension("avif");
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(parent).map_err(|e| format!("Unable to create {}: {e}", parent.display()))?;
+                    }
+                    dest
                 } else {
                     output.clone()
                 }
@@ -173,23 +328,69 @@ fn run() -> Result<(), BoxError> {
             .with_speed(speed)
             .with_alpha_quality(alpha_quality)
             .with_internal_color_space(color_space)
+            .with_lossless(lossless)
             .with_alpha_color_mode(if dirty_alpha {
                 AlphaColorMode::UnassociatedDirty
             } else {
                 AlphaColorMode::UnassociatedClean
             })
-            .with_num_threads(Some(threads).filter(|&n| n > 0));
+            .with_num_threads(Some(threads).filter(|&n| n > 0))
+            .with_depth(depth)
+            .with_hdr_transfer_characteristic(hdr_transfer)
+            .with_loop_count(loop_count);
+        let enc = match max_size {
+            Some(bytes) => enc.with_target_size(bytes),
+            None => enc,
+        };
+        let enc = match target_ssim {
+            Some(ssim) => enc.with_target_ssim(ssim),
+            None => enc,
+        };
         let EncodedImage {
             avif_file,
             color_byte_size,
             alpha_byte_size,
-            ..
-        } = enc.encode_rgba(img.as_ref())?;
+            quality: chosen_quality,
+        } = match sequence {
+            Some(frames) => {
+                if !quiet && (depth > 8 || hdr_transfer.is_some()) {
+                    eprintln!("warning: --depth/--hdr are only supported for still images; encoding this animation as 8-bit SDR");
+                }
+                if !quiet && frames.iter().any(|(img, _)| img.pixels().any(|px| px.a != 255)) {
+                    eprintln!("warning: animated AVIF output has no alpha track yet; transparency in this animation will be dropped");
+                }
+                let fallback_duration = Duration::from_secs_f32(1. / fallback_fps.max(1.));
+                const DEFAULT_GOP: u32 = 30;
+                let keyframe_interval = keyframe_interval
+                    .unwrap_or_else(|| if frames.len() as u32 <= DEFAULT_GOP { 1 } else { DEFAULT_GOP });
+                enc.encode_rgba_sequence(
+                    frames.iter().map(|(img, duration)| {
+                        let duration = if duration.is_zero() { fallback_duration } else { *duration };
+                        (img.as_ref(), duration)
+                    }),
+                    keyframe_interval,
+                )?
+            }
+            None if depth > 8 => {
+                let still = still.as_ref().expect("loaded still image");
+                let widened: Vec<rgb::RGBA16> = still
+                    .pixels()
+                    .map(|px| rgb::RGBA16::new(widen(px.r), widen(px.g), widen(px.b), widen(px.a)))
+                    .collect();
+                enc.encode_rgba16(ravif::Img::new(widened, still.width(), still.height()).as_ref())?
+            }
+            None => enc.encode_rgba(still.as_ref().expect("loaded still image").as_ref())?,
+        };
         match out_path {
             MaybePath::Path(ref p) => {
                 if !quiet {
+                    let quality_suffix = if max_size.is_some() || target_ssim.is_some() {
+                        format!(", quality {chosen_quality:.0} chosen automatically")
+                    } else {
+                        String::new()
+                    };
                     println!(
-                        "{}: {}KB ({color_byte_size}B color, {alpha_byte_size}B alpha, {}B HEIF)",
+                        "{}: {}KB ({color_byte_size}B color, {alpha_byte_size}B alpha, {}B HEIF{quality_suffix})",
                         p.display(),
                         (avif_file.len() + 999) / 1000,
                         avif_file.len() - color_byte_size - alpha_byte_size
@@ -205,7 +406,7 @@ fn run() -> Result<(), BoxError> {
 
     let failures = files
         .into_par_iter()
-        .map(|path| {
+        .map(|(path, relative)| {
             let tmp;
             let (data, path_str): (_, &dyn std::fmt::Display) = match path {
                 MaybePath::Stdio => {
@@ -221,7 +422,7 @@ fn run() -> Result<(), BoxError> {
                     (data, &tmp)
                 }
             };
-            process(data, &path).map_err(|e| BoxError::from(format!("{path_str}: error: {e}")))
+            process(data, &path, relative.as_deref()).map_err(|e| BoxError::from(format!("{path_str}: error: {e}")))
         })
         .filter_map(|res| res.err())
         .collect::<Vec<BoxError>>();